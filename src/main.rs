@@ -1,12 +1,21 @@
 use axum::{
     extract::{FromRequest, FromRequestParts, Request},
     handler::Handler,
-    http::{HeaderMap, StatusCode},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     routing::get,
-    Router,
+    Json, Router,
 };
-use std::{fmt::Display, future::Future, pin::Pin, time::Duration};
+use std::{
+    convert::Infallible,
+    fmt::Display,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tower::{Layer, Service, ServiceExt};
 use tower_http::trace::TraceLayer;
 use tracing::Span;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
@@ -19,28 +28,88 @@ fn trace_error<E: std::error::Error>(e: &E) {
     );
 }
 
+/// An error handler that, like a [`Handler`], may pull in extractors before receiving the error
+/// itself as its final argument.
+///
+/// Only [`FromRequestParts`] extractors are supported: by the time the error handler runs, the
+/// request body has already been consumed by the main handler (or was never fully available, on
+/// an extractor rejection), so a body extractor wouldn't have anything to read.
+pub trait ErrorHandler<T, S, FErr>: Clone + Send + Sized + 'static {
+    type Future: Future<Output = Response> + Send + 'static;
+
+    fn call(self, err: FErr, parts: Parts, state: S) -> Self::Future;
+}
+
+impl<FE, FEFut, FERes, S, FErr> ErrorHandler<(), S, FErr> for FE
+where
+    FE: FnOnce(FErr) -> FEFut + Clone + Send + 'static,
+    FEFut: Future<Output = FERes> + Send + 'static,
+    FERes: IntoResponse,
+    S: Send + 'static,
+    FErr: Send + 'static,
+{
+    type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+    fn call(self, err: FErr, _parts: Parts, _state: S) -> Self::Future {
+        Box::pin(async move { self(err).await.into_response() })
+    }
+}
+
+macro_rules! impl_error_handler {
+    ([$($ty:ident),*]) => {
+        #[allow(non_snake_case, unused_mut, unused_variables)]
+        impl<FE, FEFut, FERes, S, FErr, $($ty,)*> ErrorHandler<($($ty,)*), S, FErr> for FE
+        where
+            FE: FnOnce($($ty,)* FErr) -> FEFut + Clone + Send + 'static,
+            FEFut: Future<Output = FERes> + Send + 'static,
+            FERes: IntoResponse,
+            FErr: Send + 'static,
+            S: Send + Sync + 'static,
+            $( $ty: FromRequestParts<S> + Send, )*
+        {
+            type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+            fn call(self, err: FErr, mut parts: Parts, state: S) -> Self::Future {
+                Box::pin(async move {
+                    let state = &state;
+
+                    $(
+                        let $ty = match $ty::from_request_parts(&mut parts, state).await {
+                            Ok(value) => value,
+                            Err(rejection) => return rejection.into_response(),
+                        };
+                    )*
+
+                    self($($ty,)* err).await.into_response()
+                })
+            }
+        }
+    };
+}
+
 #[derive(Clone)]
 pub struct ErrorHandledHandler<F, FE>(pub F, pub FE);
 
-impl<F, FE, FFut, FEFut, FOk, FErr, FERes, S> Handler<((),), S> for ErrorHandledHandler<F, FE>
+impl<F, FE, FFut, FOk, FErr, S, ErrArgs> Handler<((), ErrArgs), S> for ErrorHandledHandler<F, FE>
 where
     F: FnOnce() -> FFut + Clone + Send + 'static,
-    FE: FnOnce(FErr) -> FEFut + Clone + Send + 'static,
     FFut: Future<Output = Result<FOk, FErr>> + Send,
-    FEFut: Future<Output = FERes> + Send,
     FOk: IntoResponse + Send,
-    FERes: IntoResponse,
-    FErr: std::error::Error + Send,
+    FErr: std::error::Error + Send + 'static,
+    FE: ErrorHandler<ErrArgs, S, FErr>,
+    S: Clone + Send + Sync + 'static,
 {
     type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
 
-    fn call(self, _req: Request, _state: S) -> Self::Future {
+    fn call(self, req: Request, state: S) -> Self::Future {
         Box::pin(async move {
+            let (parts, _body) = req.into_parts();
+
             match self.0().await {
                 Ok(value) => value.into_response(),
                 Err(e) => {
                     trace_error(&e);
-                    self.1(e).await.into_response()
+                    self.1.call(e, parts, state).await
                 }
             }
         })
@@ -52,16 +121,14 @@ macro_rules! impl_handler {
         [$($ty:ident),*], $last:ident
     ) => {
         #[allow(non_snake_case, unused_mut)]
-        impl<F, FE, FFut, FEFut, FOk, FErr, FERes, S, M, $($ty,)* $last> Handler<(M, $($ty,)* $last,), S> for ErrorHandledHandler<F, FE>
+        impl<F, FE, FFut, FOk, FErr, S, M, ErrArgs, $($ty,)* $last> Handler<(M, ErrArgs, $($ty,)* $last,), S> for ErrorHandledHandler<F, FE>
         where
             F: FnOnce($($ty,)* $last,) -> FFut + Clone + Send + 'static,
-            FE: FnOnce(FErr) -> FEFut + Clone + Send + 'static,
             FFut: Future<Output = Result<FOk, FErr>> + Send,
-            FEFut: Future<Output = FERes> + Send,
             FOk: IntoResponse + Send,
-            S: Send + Sync + 'static,
-            FERes: IntoResponse,
-            FErr: std::error::Error + Send,
+            S: Clone + Send + Sync + 'static,
+            FErr: std::error::Error + Send + 'static,
+            FE: ErrorHandler<ErrArgs, S, FErr>,
             $( $ty: FromRequestParts<S> + Send, )*
             $last: FromRequest<S, M> + Send,
         {
@@ -70,18 +137,19 @@ macro_rules! impl_handler {
             fn call(self, req: Request, state: S) -> Self::Future {
                 Box::pin(async move {
                     let (mut parts, body) = req.into_parts();
-                    let state = &state;
+                    let state_ref = &state;
 
                     $(
-                        let $ty = match $ty::from_request_parts(&mut parts, state).await {
+                        let $ty = match $ty::from_request_parts(&mut parts, state_ref).await {
                             Ok(value) => value,
                             Err(rejection) => return rejection.into_response(),
                         };
                     )*
 
+                    let snapshot = parts.clone();
                     let req = Request::from_parts(parts, body);
 
-                    let $last = match $last::from_request(req, state).await {
+                    let $last = match $last::from_request(req, state_ref).await {
                         Ok(value) => value,
                         Err(rejection) => return rejection.into_response(),
                     };
@@ -90,7 +158,85 @@ macro_rules! impl_handler {
                         Ok(value) => value.into_response(),
                         Err(e) => {
                             trace_error(&e);
-                            self.1(e).await.into_response()
+                            self.1.call(e, snapshot, state).await
+                        }
+                    }
+                })
+            }
+        }
+    };
+}
+
+/// Like [`ErrorHandledHandler`], except extractor rejections are funneled through the same
+/// `FErr`/error-handler pipeline as a handler-returned error, instead of the default of rendering
+/// a rejection with its own [`IntoResponse`] impl. The error handler must accept whatever `FErr`
+/// every rejection type converts into via [`From`].
+///
+/// A distinct type from `ErrorHandledHandler` (rather than a wrapper around its error handler)
+/// because Rust's coherence rules won't allow both an `impl Handler<_, _> for
+/// ErrorHandledHandler<F, FE>` generic over any `FE` and an `impl ... for ErrorHandledHandler<F,
+/// SomeWrapper<FE>>` to coexist — the first would have to rule out `FE = SomeWrapper<_>` itself.
+#[derive(Clone)]
+pub struct MapRejections<F, FE>(pub F, pub FE);
+
+macro_rules! impl_handler_with_mapped_rejections {
+    (
+        [$($ty:ident),*], $last:ident
+    ) => {
+        #[allow(non_snake_case, unused_mut)]
+        impl<F, FE, FFut, FOk, FErr, S, M, ErrArgs, $($ty,)* $last> Handler<(M, ErrArgs, $($ty,)* $last,), S> for MapRejections<F, FE>
+        where
+            F: FnOnce($($ty,)* $last,) -> FFut + Clone + Send + 'static,
+            FFut: Future<Output = Result<FOk, FErr>> + Send,
+            FOk: IntoResponse + Send,
+            S: Clone + Send + Sync + 'static,
+            FErr: std::error::Error + Send + 'static,
+            FE: ErrorHandler<ErrArgs, S, FErr>,
+            $( $ty: FromRequestParts<S> + Send, )*
+            $last: FromRequest<S, M> + Send,
+            $(
+                FErr: From<<$ty as FromRequestParts<S>>::Rejection>,
+                <$ty as FromRequestParts<S>>::Rejection: Send,
+            )*
+            FErr: From<<$last as FromRequest<S, M>>::Rejection>,
+            <$last as FromRequest<S, M>>::Rejection: Send,
+        {
+            type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+            fn call(self, req: Request, state: S) -> Self::Future {
+                Box::pin(async move {
+                    let MapRejections(f, error_handler) = self;
+                    let (mut parts, body) = req.into_parts();
+                    let snapshot = parts.clone();
+                    let state_ref = &state;
+
+                    $(
+                        let $ty = match $ty::from_request_parts(&mut parts, state_ref).await {
+                            Ok(value) => value,
+                            Err(rejection) => {
+                                let e: FErr = rejection.into();
+                                trace_error(&e);
+                                return error_handler.call(e, snapshot.clone(), state).await;
+                            }
+                        };
+                    )*
+
+                    let req = Request::from_parts(parts, body);
+
+                    let $last = match $last::from_request(req, state_ref).await {
+                        Ok(value) => value,
+                        Err(rejection) => {
+                            let e: FErr = rejection.into();
+                            trace_error(&e);
+                            return error_handler.call(e, snapshot, state).await;
+                        }
+                    };
+
+                    match f($($ty,)* $last,).await {
+                        Ok(value) => value.into_response(),
+                        Err(e) => {
+                            trace_error(&e);
+                            error_handler.call(e, snapshot, state).await
                         }
                     }
                 })
@@ -130,6 +276,276 @@ macro_rules! all_the_tuples {
 }
 
 all_the_tuples!(impl_handler);
+all_the_tuples!(impl_handler_with_mapped_rejections);
+
+macro_rules! all_the_tuples_for_error_handler {
+    ($name:ident) => {
+        $name!([T1]);
+        $name!([T1, T2]);
+        $name!([T1, T2, T3]);
+        $name!([T1, T2, T3, T4]);
+        $name!([T1, T2, T3, T4, T5]);
+        $name!([T1, T2, T3, T4, T5, T6]);
+        $name!([T1, T2, T3, T4, T5, T6, T7]);
+        $name!([T1, T2, T3, T4, T5, T6, T7, T8]);
+        $name!([T1, T2, T3, T4, T5, T6, T7, T8, T9]);
+        $name!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10]);
+        $name!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11]);
+        $name!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12]);
+        $name!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13]);
+        $name!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14]);
+        $name!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15]);
+        $name!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, T16]);
+    };
+}
+
+all_the_tuples_for_error_handler!(impl_error_handler);
+
+/// A [`tower::Layer`] counterpart to [`ErrorHandledHandler`], following the shape of axum's
+/// `HandleErrorLayer`: instead of wrapping a single handler, it can be `.layer()`ed onto an
+/// entire [`Router`] or a subtree of routes, catching the `Error` of whatever [`Service`] it
+/// wraps and turning it into a [`Response`] through an [`ErrorHandler`].
+///
+/// The error handler only ever sees extractors that work without application state (`S = ()`),
+/// since by the time a `Service` is layered there generally is no typed state left to extract
+/// from — it has already been folded into the service via `Router::with_state`.
+pub struct ErrorHandlingLayer<FE, ErrArgs> {
+    error_handler: FE,
+    _marker: PhantomData<fn() -> ErrArgs>,
+}
+
+impl<FE, ErrArgs> ErrorHandlingLayer<FE, ErrArgs> {
+    pub fn new(error_handler: FE) -> Self {
+        Self {
+            error_handler,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<FE: Clone, ErrArgs> Clone for ErrorHandlingLayer<FE, ErrArgs> {
+    fn clone(&self) -> Self {
+        Self {
+            error_handler: self.error_handler.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Svc, FE, ErrArgs> Layer<Svc> for ErrorHandlingLayer<FE, ErrArgs>
+where
+    FE: Clone,
+{
+    type Service = ErrorHandlingService<Svc, FE, ErrArgs>;
+
+    fn layer(&self, inner: Svc) -> Self::Service {
+        ErrorHandlingService {
+            inner,
+            error_handler: self.error_handler.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+pub struct ErrorHandlingService<Svc, FE, ErrArgs> {
+    inner: Svc,
+    error_handler: FE,
+    _marker: PhantomData<fn() -> ErrArgs>,
+}
+
+impl<Svc: Clone, FE: Clone, ErrArgs> Clone for ErrorHandlingService<Svc, FE, ErrArgs> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            error_handler: self.error_handler.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Svc, FE, ErrArgs> Service<Request> for ErrorHandlingService<Svc, FE, ErrArgs>
+where
+    Svc: Service<Request, Response = Response> + Clone + Send + 'static,
+    Svc::Future: Send,
+    Svc::Error: std::error::Error + Send + 'static,
+    FE: ErrorHandler<ErrArgs, (), Svc::Error>,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Deferred to `call`, same trick `axum::error_handling::HandleError` uses: we clone the
+        // inner service and run `poll_ready` on the clone right before `call`, so this service is
+        // always considered ready.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let error_handler = self.error_handler.clone();
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let snapshot = parts.clone();
+            let req = Request::from_parts(parts, body);
+
+            match inner.oneshot(req).await {
+                Ok(response) => Ok(response),
+                Err(e) => {
+                    trace_error(&e);
+                    Ok(error_handler.call(e, snapshot, ()).await)
+                }
+            }
+        })
+    }
+}
+
+/// An [RFC 7807](https://www.rfc-editor.org/rfc/rfc7807) "problem details" error response.
+///
+/// Most apps end up hand-rolling "render my error as JSON for API clients but HTML/plain text
+/// for browsers" at least once; `ProblemResponse` does that content negotiation for you based on
+/// the request's `Accept` header (see [`ProblemResponse::with_accept`]), falling back to
+/// `application/problem+json` when the client didn't ask for anything else.
+pub struct ProblemResponse {
+    pub status: StatusCode,
+    pub title: String,
+    pub detail: Option<String>,
+    pub r#type: Option<String>,
+    accept: Option<HeaderValue>,
+}
+
+impl ProblemResponse {
+    pub fn new(status: StatusCode, title: impl Into<String>) -> Self {
+        Self {
+            status,
+            title: title.into(),
+            detail: None,
+            r#type: None,
+            accept: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_type(mut self, r#type: impl Into<String>) -> Self {
+        self.r#type = Some(r#type.into());
+        self
+    }
+
+    /// Records the client's `Accept` header so [`IntoResponse`] can pick the right
+    /// representation; typically pulled in by an error handler as a `HeaderMap` extractor.
+    pub fn with_accept(mut self, headers: &HeaderMap) -> Self {
+        self.accept = headers.get(header::ACCEPT).cloned();
+        self
+    }
+
+    /// Lowers any `std::error::Error` into a `500` problem response with no further detail.
+    ///
+    /// For an error type that wants its own status code, implement [`ProblemDetails`] for it and
+    /// use [`ProblemResponse::from_details`] instead.
+    pub fn from_error<E: std::error::Error>(error: &E) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+    }
+
+    pub fn from_details<E: ProblemDetails>(error: &E) -> Self {
+        let response = Self::new(error.status_code(), error.to_string());
+        match error.problem_type() {
+            Some(r#type) => response.with_type(r#type),
+            None => response,
+        }
+    }
+
+    /// Lowercased, since HTTP media-type tokens (unlike parameter values) are case-insensitive.
+    fn accept_str(&self) -> String {
+        self.accept
+            .as_ref()
+            .and_then(|accept| accept.to_str().ok())
+            .unwrap_or("")
+            .to_ascii_lowercase()
+    }
+}
+
+/// Lets an error type pick its own [`StatusCode`] (and optional RFC 7807 `type` URI) when
+/// rendered as a [`ProblemResponse`], instead of the `500` that [`ProblemResponse::from_error`]
+/// defaults to.
+pub trait ProblemDetails: std::error::Error {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn problem_type(&self) -> Option<String> {
+        None
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ProblemJson<'a> {
+    r#type: &'a str,
+    title: &'a str,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+}
+
+/// Escapes the characters that matter when splicing a string into HTML text content, since
+/// `title`/`detail` routinely carry request-derived content (e.g. a rejection message echoing
+/// back the invalid input).
+fn escape_html(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+impl IntoResponse for ProblemResponse {
+    fn into_response(self) -> Response {
+        let accept = self.accept_str();
+
+        if accept.contains("text/html") {
+            let title = escape_html(&self.title);
+            let body = format!(
+                "<!doctype html><title>{title}</title><h1>{title}</h1>{detail}",
+                detail = self
+                    .detail
+                    .as_deref()
+                    .map(|detail| format!("<p>{}</p>", escape_html(detail)))
+                    .unwrap_or_default(),
+            );
+            return (self.status, [(header::CONTENT_TYPE, "text/html")], body).into_response();
+        }
+
+        if accept.contains("text/plain") {
+            let mut body = self.title.clone();
+            if let Some(detail) = &self.detail {
+                body.push_str(": ");
+                body.push_str(detail);
+            }
+            return (self.status, [(header::CONTENT_TYPE, "text/plain")], body).into_response();
+        }
+
+        let status = self.status;
+        let body = ProblemJson {
+            r#type: self.r#type.as_deref().unwrap_or("about:blank"),
+            title: &self.title,
+            status: status.as_u16(),
+            detail: self.detail.as_deref(),
+        };
+
+        (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(body),
+        )
+            .into_response()
+    }
+}
 
 #[derive(Debug)]
 struct MyErr;
@@ -142,14 +558,46 @@ impl Display for MyErr {
 
 impl std::error::Error for MyErr {}
 
+// Only needed to opt `/mapped-rejections` into `MapRejections` below: `HeaderMap` and `Request`
+// never fail to extract, but `MapRejections` still needs `MyErr: From<Infallible>` to route
+// their (unreachable) rejections through `handle_error`.
+impl From<std::convert::Infallible> for MyErr {
+    fn from(err: std::convert::Infallible) -> Self {
+        match err {}
+    }
+}
+
+// The default status code (500) is fine for `MyErr`, so there's nothing to override here.
+impl ProblemDetails for MyErr {}
+
 /// Can use arbitrary extractors here
 async fn handler(_header: HeaderMap, _req: Request) -> Result<StatusCode, MyErr> {
     Err(MyErr)
 }
 
-/// Could also allow extractors here if we implement the trait
-async fn handle_error(_err: MyErr) -> StatusCode {
-    StatusCode::INTERNAL_SERVER_ERROR
+/// Can also use arbitrary `FromRequestParts` extractors here, alongside the error
+async fn handle_error(headers: HeaderMap, err: MyErr) -> ProblemResponse {
+    ProblemResponse::from_details(&err).with_accept(&headers)
+}
+
+/// A plain `tower::Service` (not a `Handler`) whose errors are caught by `ErrorHandlingLayer`
+/// instead of `ErrorHandledHandler`, demonstrating that the layer can sit in front of things
+/// other than a single route.
+#[derive(Clone)]
+struct FallibleService;
+
+impl Service<Request> for FallibleService {
+    type Response = Response;
+    type Error = MyErr;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, MyErr>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request) -> Self::Future {
+        Box::pin(async { Err(MyErr) })
+    }
 }
 
 #[tokio::main]
@@ -165,6 +613,18 @@ async fn main() {
 
     let app = Router::new()
         .route("/", get(ErrorHandledHandler(handler, handle_error)))
+        // Opts this route into funneling extractor rejections through `handle_error` too,
+        // instead of the default of rendering a rejection with its own `IntoResponse` impl.
+        .route(
+            "/mapped-rejections",
+            get(MapRejections(handler, handle_error)),
+        )
+        // Instead of wrapping each handler individually, a whole (sub)service can opt into the
+        // same error policy by layering `ErrorHandlingLayer` over it.
+        .route_service(
+            "/service",
+            ErrorHandlingLayer::new(handle_error).layer(FallibleService),
+        )
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(|request: &Request<_>| {